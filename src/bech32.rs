@@ -1,5 +1,22 @@
 pub const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
 
+/// Which checksum constant a bech32 string is verified/created against.
+/// Classic bech32 (BIP-173) uses `1`; bech32m (BIP-350) uses `0x2bc830a3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumVariant {
+    Bech32,
+    Bech32m,
+}
+
+impl ChecksumVariant {
+    fn constant(self) -> u32 {
+        match self {
+            ChecksumVariant::Bech32 => 1,
+            ChecksumVariant::Bech32m => 0x2bc830a3,
+        }
+    }
+}
+
 pub struct Bech32;
 
 impl Bech32 {
@@ -29,18 +46,26 @@ impl Bech32 {
         result
     }
 
-    fn verify_checksum(hrp: &str, data: &[u8]) -> bool {
+    fn verify_checksum(hrp: &str, data: &[u8]) -> Option<ChecksumVariant> {
         let mut values = Self::hrp_expand(hrp);
         values.extend_from_slice(data);
-        Self::polymod(&values) == 1
+        let polymod = Self::polymod(&values);
+
+        if polymod == ChecksumVariant::Bech32.constant() {
+            Some(ChecksumVariant::Bech32)
+        } else if polymod == ChecksumVariant::Bech32m.constant() {
+            Some(ChecksumVariant::Bech32m)
+        } else {
+            None
+        }
     }
 
-    fn create_checksum(hrp: &str, data: &[u8]) -> Vec<u8> {
+    fn create_checksum(hrp: &str, data: &[u8], variant: ChecksumVariant) -> Vec<u8> {
         let mut values = Self::hrp_expand(hrp);
         values.extend_from_slice(data);
         values.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
 
-        let polymod = Self::polymod(&values) ^ 1;
+        let polymod = Self::polymod(&values) ^ variant.constant();
         let mut checksum = Vec::new();
         for i in 0..6 {
             checksum.push(((polymod >> (5 * (5 - i))) & 31) as u8);
@@ -49,8 +74,12 @@ impl Bech32 {
     }
 
     pub fn encode(hrp: &str, data: &[u8]) -> String {
+        Self::encode_with_variant(hrp, data, ChecksumVariant::Bech32)
+    }
+
+    pub fn encode_with_variant(hrp: &str, data: &[u8], variant: ChecksumVariant) -> String {
         let mut combined = data.to_vec();
-        combined.extend_from_slice(&Self::create_checksum(hrp, data));
+        combined.extend_from_slice(&Self::create_checksum(hrp, data, variant));
 
         let mut result = String::from(hrp);
         result.push('1');
@@ -61,6 +90,11 @@ impl Bech32 {
     }
 
     pub fn decode(bech: &str) -> Result<(String, Vec<u8>), String> {
+        let (hrp, data, _variant) = Self::decode_with_variant(bech)?;
+        Ok((hrp, data))
+    }
+
+    pub fn decode_with_variant(bech: &str) -> Result<(String, Vec<u8>, ChecksumVariant), String> {
         if bech.chars().any(|c| (c as u32) < 33 || (c as u32) > 126) {
             return Err("Invalid character".to_string());
         }
@@ -91,11 +125,9 @@ impl Bech32 {
             .map(|c| CHARSET.find(c).unwrap() as u8)
             .collect();
 
-        if !Self::verify_checksum(hrp, &data) {
-            return Err("Invalid checksum".to_string());
-        }
+        let variant = Self::verify_checksum(hrp, &data).ok_or("Invalid checksum")?;
 
-        Ok((hrp.to_string(), data[..data.len() - 6].to_vec()))
+        Ok((hrp.to_string(), data[..data.len() - 6].to_vec(), variant))
     }
 }
 
@@ -1,18 +1,332 @@
 use bitcoin_hashes::sha256;
-use regex::Regex;
 use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::bech32::{Bech32, CHARSET, convert_bits};
+use crate::bech32::{convert_bits, Bech32, ChecksumVariant, CHARSET};
+
+/// Errors for malformed invoices: bad bech32, wrong lengths, or unparseable fields.
+#[derive(Debug)]
+pub enum ParseError {
+    Bech32(String),
+    InvalidLength(String),
+    InvalidField(String),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Bech32(msg) => write!(f, "bech32 error: {}", msg),
+            ParseError::InvalidLength(msg) => write!(f, "invalid length: {}", msg),
+            ParseError::InvalidField(msg) => write!(f, "invalid field: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Errors for invoices that parse cleanly but break a BOLT11 rule.
+#[derive(Debug)]
+pub enum SemanticError {
+    MissingDescription,
+    ConflictingDescription,
+    InvalidPaymentHashLength,
+    InvalidSignature,
+    PayeeKeyMismatch,
+    AmountOutOfRange,
+    MissingPaymentSecretFeatureBit,
+}
+
+impl std::fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            SemanticError::MissingDescription => {
+                "invoice must include either a description or a description hash"
+            }
+            SemanticError::ConflictingDescription => {
+                "invoice cannot include both a description and a description hash"
+            }
+            SemanticError::InvalidPaymentHashLength => "payment hash must be exactly 32 bytes",
+            SemanticError::InvalidSignature => "signature is not recoverable",
+            SemanticError::PayeeKeyMismatch => "recovered public key does not match the 'n' tag",
+            SemanticError::AmountOutOfRange => "amount exceeds the total bitcoin supply",
+            SemanticError::MissingPaymentSecretFeatureBit => {
+                "payment secret present but 'payment_secret' feature bit not set"
+            }
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for SemanticError {}
+
+/// Top-level decode error: lets callers distinguish "couldn't parse" from "parsed but invalid".
+#[derive(Debug)]
+pub enum DecodeError {
+    Parse(ParseError),
+    Semantic(SemanticError),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Parse(e) => write!(f, "{}", e),
+            DecodeError::Semantic(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<ParseError> for DecodeError {
+    fn from(e: ParseError) -> Self {
+        DecodeError::Parse(e)
+    }
+}
+
+impl From<SemanticError> for DecodeError {
+    fn from(e: SemanticError) -> Self {
+        DecodeError::Semantic(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteHintHop {
+    pub pubkey: [u8; 33],
+    pub short_channel_id: u64,
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+    pub cltv_expiry_delta: u16,
+}
+
+impl RouteHintHop {
+    const LEN: usize = 51;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(&self.pubkey);
+        bytes.extend_from_slice(&self.short_channel_id.to_be_bytes());
+        bytes.extend_from_slice(&self.fee_base_msat.to_be_bytes());
+        bytes.extend_from_slice(&self.fee_proportional_millionths.to_be_bytes());
+        bytes.extend_from_slice(&self.cltv_expiry_delta.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != Self::LEN {
+            return Err("Invalid route hint hop length".to_string());
+        }
+
+        let mut pubkey = [0u8; 33];
+        pubkey.copy_from_slice(&bytes[0..33]);
+
+        Ok(RouteHintHop {
+            pubkey,
+            short_channel_id: u64::from_be_bytes(bytes[33..41].try_into().unwrap()),
+            fee_base_msat: u32::from_be_bytes(bytes[41..45].try_into().unwrap()),
+            fee_proportional_millionths: u32::from_be_bytes(bytes[45..49].try_into().unwrap()),
+            cltv_expiry_delta: u16::from_be_bytes(bytes[49..51].try_into().unwrap()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteHint(pub Vec<RouteHintHop>);
+
+impl RouteHint {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.0.len() * RouteHintHop::LEN);
+        for hop in &self.0 {
+            bytes.extend_from_slice(&hop.to_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() % RouteHintHop::LEN != 0 {
+            return Err("Invalid route hint length".to_string());
+        }
+
+        let hops = bytes
+            .chunks(RouteHintHop::LEN)
+            .map(RouteHintHop::from_bytes)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RouteHint(hops))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Fallback {
+    SegWitProgram { version: u8, program: Vec<u8> },
+    PubKeyHash([u8; 20]),
+    ScriptHash([u8; 20]),
+}
+
+impl Fallback {
+    const SEGWIT_PROGRAM_MIN_LEN: usize = 2;
+    const SEGWIT_PROGRAM_MAX_LEN: usize = 40;
+
+    fn to_5bit(&self) -> Result<Vec<u8>, String> {
+        let (version, program): (u8, &[u8]) = match self {
+            Fallback::SegWitProgram { version, program } => {
+                if !(Self::SEGWIT_PROGRAM_MIN_LEN..=Self::SEGWIT_PROGRAM_MAX_LEN)
+                    .contains(&program.len())
+                {
+                    return Err(format!(
+                        "Segwit witness program must be {}-{} bytes",
+                        Self::SEGWIT_PROGRAM_MIN_LEN,
+                        Self::SEGWIT_PROGRAM_MAX_LEN
+                    ));
+                }
+                (*version, program.as_slice())
+            }
+            Fallback::PubKeyHash(hash) => (17, hash),
+            Fallback::ScriptHash(hash) => (18, hash),
+        };
+
+        let mut data_5bit = vec![version];
+        data_5bit.extend_from_slice(&convert_bits(program, 8, 5, true)?);
+        Ok(data_5bit)
+    }
+
+    fn from_5bit(data_5bit: &[u8]) -> Result<Self, String> {
+        let (version, program_5bit) = data_5bit.split_first().ok_or("Empty fallback field")?;
+        let program = convert_bits(program_5bit, 5, 8, false)?;
+
+        match version {
+            0..=16 => {
+                if !(Self::SEGWIT_PROGRAM_MIN_LEN..=Self::SEGWIT_PROGRAM_MAX_LEN)
+                    .contains(&program.len())
+                {
+                    return Err(format!(
+                        "Segwit witness program must be {}-{} bytes",
+                        Self::SEGWIT_PROGRAM_MIN_LEN,
+                        Self::SEGWIT_PROGRAM_MAX_LEN
+                    ));
+                }
+                Ok(Fallback::SegWitProgram {
+                    version: *version,
+                    program,
+                })
+            }
+            17 => {
+                let hash: [u8; 20] = program
+                    .try_into()
+                    .map_err(|_| "Invalid P2PKH fallback length")?;
+                Ok(Fallback::PubKeyHash(hash))
+            }
+            18 => {
+                let hash: [u8; 20] = program
+                    .try_into()
+                    .map_err(|_| "Invalid P2SH fallback length")?;
+                Ok(Fallback::ScriptHash(hash))
+            }
+            _ => Err(format!("Unknown fallback version: {}", version)),
+        }
+    }
+}
+
+/// A BOLT9/11 feature bit vector, stored as the set of feature bits that are set.
+/// Named accessors use the "optional" (odd) bit of each feature pair, matching
+/// how most wallets advertise these features today.
+#[derive(Debug, Clone, Default)]
+pub struct Features {
+    bits: BTreeSet<usize>,
+}
+
+impl Features {
+    const VAR_ONION_OPTIN: usize = 9;
+    const PAYMENT_SECRET: usize = 15;
+    const BASIC_MPP: usize = 17;
+
+    pub fn new() -> Self {
+        Features::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    pub fn is_bit_set(&self, bit: usize) -> bool {
+        self.bits.contains(&bit)
+    }
+
+    pub fn set_bit(&mut self, bit: usize) {
+        self.bits.insert(bit);
+    }
+
+    pub fn bits(&self) -> Vec<usize> {
+        self.bits.iter().copied().collect()
+    }
+
+    pub fn var_onion_optin(&self) -> bool {
+        self.is_bit_set(Self::VAR_ONION_OPTIN)
+    }
+
+    pub fn set_var_onion_optin(&mut self) {
+        self.set_bit(Self::VAR_ONION_OPTIN)
+    }
+
+    pub fn payment_secret(&self) -> bool {
+        self.is_bit_set(Self::PAYMENT_SECRET)
+    }
+
+    pub fn set_payment_secret(&mut self) {
+        self.set_bit(Self::PAYMENT_SECRET)
+    }
+
+    pub fn basic_mpp(&self) -> bool {
+        self.is_bit_set(Self::BASIC_MPP)
+    }
+
+    pub fn set_basic_mpp(&mut self) {
+        self.set_bit(Self::BASIC_MPP)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let max_bit = match self.bits.iter().next_back() {
+            Some(bit) => *bit,
+            None => return Vec::new(),
+        };
+
+        let num_bytes = max_bit / 8 + 1;
+        let mut bytes = vec![0u8; num_bytes];
+        for &bit in &self.bits {
+            let byte_idx = bit / 8;
+            bytes[num_bytes - 1 - byte_idx] |= 1 << (bit % 8);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let len = bytes.len();
+        let mut bits = BTreeSet::new();
+        for (byte_idx, &byte) in bytes.iter().enumerate() {
+            let feature_byte_idx = len - 1 - byte_idx;
+            for bit_idx in 0..8 {
+                if byte & (1 << bit_idx) != 0 {
+                    bits.insert(feature_byte_idx * 8 + bit_idx);
+                }
+            }
+        }
+        Features { bits }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct InvoiceBolt11 {
     pub currency: String,
-    pub amount: Option<f64>,
+    pub amount_msat: Option<u64>,
     pub date: u64,
     pub paymenthash: Vec<u8>,
     pub tags: Vec<(char, Vec<u8>)>,
+    pub route_hints: Vec<RouteHint>,
+    pub fallbacks: Vec<Fallback>,
+    pub payment_secret: Option<[u8; 32]>,
+    pub payee_pubkey: Option<PublicKey>,
+    pub min_final_cltv_expiry: Option<u64>,
+    pub features: Features,
     pub signature: Option<Vec<u8>>,
     pub pubkey: Option<PublicKey>,
 }
@@ -21,23 +335,38 @@ impl InvoiceBolt11 {
     pub fn new() -> Self {
         InvoiceBolt11 {
             currency: "bc".to_string(),
-            amount: None,
+            amount_msat: None,
             date: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
             paymenthash: Vec::new(),
             tags: Vec::new(),
+            route_hints: Vec::new(),
+            fallbacks: Vec::new(),
+            payment_secret: None,
+            payee_pubkey: None,
+            min_final_cltv_expiry: None,
+            features: Features::new(),
             signature: None,
             pubkey: None,
         }
     }
 
-    pub fn with_amount(mut self, amount: f64) -> Self {
-        self.amount = Some(amount);
+    pub fn with_amount(mut self, amount_btc: f64) -> Self {
+        self.amount_msat = Some((amount_btc * 1e11).round() as u64);
+        self
+    }
+
+    pub fn with_amount_msat(mut self, amount_msat: u64) -> Self {
+        self.amount_msat = Some(amount_msat);
         self
     }
 
+    pub fn amount_btc(&self) -> Option<f64> {
+        self.amount_msat.map(|msat| msat as f64 / 1e11)
+    }
+
     pub fn with_currency(mut self, currency: &str) -> Self {
         self.currency = currency.to_string();
         self
@@ -71,17 +400,87 @@ impl InvoiceBolt11 {
         self.tags.push(('x', bits));
         self
     }
+
+    pub fn add_route_hint(mut self, hint: RouteHint) -> Self {
+        self.tags.push(('r', hint.to_bytes()));
+        self
+    }
+
+    pub fn add_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallbacks.push(fallback);
+        self
+    }
+
+    pub fn with_payment_secret(mut self, secret: [u8; 32]) -> Self {
+        self.payment_secret = Some(secret);
+        self
+    }
+
+    pub fn with_payee_pubkey(mut self, pubkey: PublicKey) -> Self {
+        self.payee_pubkey = Some(pubkey);
+        self
+    }
+
+    pub fn with_min_final_cltv_expiry(mut self, cltv: u64) -> Self {
+        self.min_final_cltv_expiry = Some(cltv);
+        self
+    }
+
+    pub fn with_feature_bit(mut self, bit: usize) -> Self {
+        self.features.set_bit(bit);
+        self
+    }
+
+    /// The BOLT11 rule that a payment secret implies the `payment_secret` feature bit.
+    fn payment_secret_feature_ok(&self) -> bool {
+        self.payment_secret.is_none() || self.features.payment_secret()
+    }
+
+    /// Checks the BOLT11 rules that aren't implied by a successful parse:
+    /// exactly one of description/description-hash, a recoverable signature,
+    /// a 32-byte payment hash, an amount within the total bitcoin supply, and
+    /// (if a payment secret is present) the matching feature bit.
+    /// `payment_secret`'s 32-byte length is already enforced by its `[u8; 32]` type.
+    pub fn validate(&self) -> Result<(), SemanticError> {
+        let has_description = self.tags.iter().any(|(tag, _)| *tag == 'd');
+        let has_description_hash = self.tags.iter().any(|(tag, _)| *tag == 'h');
+
+        if !has_description && !has_description_hash {
+            return Err(SemanticError::MissingDescription);
+        }
+        if has_description && has_description_hash {
+            return Err(SemanticError::ConflictingDescription);
+        }
+
+        if self.signature.is_none() || self.pubkey.is_none() {
+            return Err(SemanticError::InvalidSignature);
+        }
+
+        if self.paymenthash.len() != 32 {
+            return Err(SemanticError::InvalidPaymentHashLength);
+        }
+
+        const MAX_MSAT: u64 = 21_000_000 * 100_000_000_000;
+        if let Some(amount_msat) = self.amount_msat {
+            if amount_msat > MAX_MSAT {
+                return Err(SemanticError::AmountOutOfRange);
+            }
+        }
+
+        if !self.payment_secret_feature_ok() {
+            return Err(SemanticError::MissingPaymentSecretFeatureBit);
+        }
+
+        Ok(())
+    }
 }
 
 pub fn invoice_encode(addr: &InvoiceBolt11, privkey: &str) -> Result<String, String> {
     let mut hrp = "ln".to_string();
     hrp.push_str(&addr.currency);
 
-    if let Some(amount) = addr.amount {
-        if (amount * 1e12) % 10.0 != 0.0 {
-            return Err("Too many decimal places in amount".to_string());
-        }
-        hrp.push_str(&shorten_amount(amount));
+    if let Some(amount_msat) = addr.amount_msat {
+        hrp.push_str(&shorten_amount(amount_msat)?);
     }
 
     let mut data = Vec::new();
@@ -98,7 +497,7 @@ pub fn invoice_encode(addr: &InvoiceBolt11, privkey: &str) -> Result<String, Str
     let mut tags_set = HashSet::new();
 
     for (tag_char, tag_data) in &addr.tags {
-        if ['d', 'h', 'n', 'x'].contains(tag_char) {
+        if ['d', 'h', 'x'].contains(tag_char) {
             if tags_set.contains(tag_char) {
                 return Err(format!("Duplicate '{}' tag", tag_char));
             }
@@ -108,6 +507,7 @@ pub fn invoice_encode(addr: &InvoiceBolt11, privkey: &str) -> Result<String, Str
             'd' => tagged_field('d', tag_data)?,
             'h' => tagged_field('h', tag_data)?,
             'x' => tagged_field('x', tag_data)?,
+            'r' => tagged_field('r', tag_data)?,
             _ => return Err(format!("Unknown tag: {}", tag_char)),
         };
 
@@ -122,13 +522,52 @@ pub fn invoice_encode(addr: &InvoiceBolt11, privkey: &str) -> Result<String, Str
         return Err("Cannot include both 'd' and 'h'".to_string());
     }
 
+    for fallback in &addr.fallbacks {
+        let fallback_5bit = fallback.to_5bit()?;
+        let f_tag = tagged_field_from_5bit('f', &fallback_5bit)?;
+        data_5bit.extend_from_slice(&f_tag);
+    }
+
+    if let Some(secret) = &addr.payment_secret {
+        let s_tag = tagged_field('s', secret)?;
+        data_5bit.extend_from_slice(&s_tag);
+    }
+
+    if let Some(cltv) = addr.min_final_cltv_expiry {
+        let mut bits = Vec::new();
+        let mut val = cltv;
+        while val > 0 {
+            bits.insert(0, (val & 0x1f) as u8);
+            val >>= 5;
+        }
+        if bits.is_empty() {
+            bits.push(0);
+        }
+        let c_tag = tagged_field_from_5bit('c', &bits)?;
+        data_5bit.extend_from_slice(&c_tag);
+    }
+
+    if let Some(pubkey) = &addr.payee_pubkey {
+        let n_tag = tagged_field('n', &pubkey.serialize())?;
+        data_5bit.extend_from_slice(&n_tag);
+    }
+
+    if !addr.payment_secret_feature_ok() {
+        return Err("Payment secret present but 'payment_secret' feature bit not set".to_string());
+    }
+
+    if !addr.features.is_empty() {
+        let nine_tag = tagged_field('9', &addr.features.to_bytes())?;
+        data_5bit.extend_from_slice(&nine_tag);
+    }
+
     let secp = Secp256k1::new();
     let privkey = hex::decode(privkey).map_err(|_| "Invalid private key")?;
     let secret_key = SecretKey::from_byte_array(privkey.try_into().unwrap()).unwrap();
 
-    // Prepare message for signing (HRP + data in 5-bit form)
+    // Prepare message for signing (HRP + data in 5-bit form), zero-padded to a byte boundary
     let mut msg_preimage = hrp.as_bytes().to_vec();
-    let data_bytes = convert_bits(&data_5bit, 5, 8, false)?;
+    let data_bytes = convert_bits(&data_5bit, 5, 8, true)?;
     msg_preimage.extend_from_slice(&data_bytes);
 
     let msg_hash = sha256::Hash::hash(&msg_preimage);
@@ -146,37 +585,37 @@ pub fn invoice_encode(addr: &InvoiceBolt11, privkey: &str) -> Result<String, Str
     Ok(Bech32::encode(&hrp, &data_5bit))
 }
 
-pub fn invoice_decode(invoice: &str) -> Result<InvoiceBolt11, String> {
-    let (hrp, data) = Bech32::decode(invoice)?;
+pub fn invoice_decode(invoice: &str) -> Result<InvoiceBolt11, DecodeError> {
+    let (hrp, data, variant) = Bech32::decode_with_variant(invoice).map_err(ParseError::Bech32)?;
+    if variant != ChecksumVariant::Bech32 {
+        return Err(ParseError::InvalidField(
+            "BOLT11 invoices must use the classic bech32 checksum, not bech32m".to_string(),
+        )
+        .into());
+    }
 
     if !hrp.starts_with("ln") {
-        return Err("Does not start with 'ln'".to_string());
+        return Err(ParseError::InvalidField("Does not start with 'ln'".to_string()).into());
     }
 
     if data.len() < 104 {
-        return Err("Too short to contain signature".to_string());
+        return Err(ParseError::InvalidLength("Too short to contain signature".to_string()).into());
     }
 
     let (data_part, sig_part) = data.split_at(data.len() - 104);
 
     let mut addr = InvoiceBolt11::new();
 
-    let amount_part = &hrp[2..];
-    let re = Regex::new(r"^([a-z]+)(.*)$").unwrap();
-    if let Some(caps) = re.captures(amount_part) {
-        addr.currency = caps[1].to_string();
-        let amount_str = &caps[2];
-        if !amount_str.is_empty() {
-            addr.amount = Some(unshorten_amount(amount_str)?);
-        }
-    }
+    let (currency, amount_msat) = parse_hrp(&hrp).map_err(ParseError::InvalidField)?;
+    addr.currency = currency;
+    addr.amount_msat = amount_msat;
 
     if data_part.len() < 7 {
-        return Err("Data too short for timestamp".to_string());
+        return Err(ParseError::InvalidLength("Data too short for timestamp".to_string()).into());
     }
 
     let timestamp_5bit = &data_part[0..7];
-    let timestamp_bits = convert_bits(timestamp_5bit, 5, 1, false)?;
+    let timestamp_bits = convert_bits(timestamp_5bit, 5, 1, false).map_err(ParseError::Bech32)?;
 
     addr.date = 0;
     for (i, bit) in timestamp_bits.iter().enumerate() {
@@ -205,23 +644,29 @@ pub fn invoice_decode(invoice: &str) -> Result<InvoiceBolt11, String> {
         let tag_data_5bit = &data_part[pos..pos + length];
         pos += length;
 
-        let tag_char = CHARSET.chars().nth(tag as usize).ok_or("Invalid tag")?;
+        let tag_char = CHARSET
+            .chars()
+            .nth(tag as usize)
+            .ok_or_else(|| ParseError::InvalidField("Invalid tag".to_string()))?;
 
         match tag_char {
             'p' => {
-                let tag_data = convert_bits(tag_data_5bit, 5, 8, false)?;
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
                 if tag_data.len() == 32 {
                     addr.paymenthash = tag_data;
                 }
             }
             'd' => {
-                let tag_data = convert_bits(tag_data_5bit, 5, 8, false)?;
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
                 if let Ok(_) = String::from_utf8(tag_data.clone()) {
                     addr.tags.push(('d', tag_data));
                 }
             }
             'h' => {
-                let tag_data = convert_bits(tag_data_5bit, 5, 8, false)?;
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
                 if tag_data.len() == 32 {
                     addr.tags.push(('h', tag_data));
                 }
@@ -229,20 +674,60 @@ pub fn invoice_decode(invoice: &str) -> Result<InvoiceBolt11, String> {
             'x' => {
                 addr.tags.push(('x', tag_data_5bit.to_vec()));
             }
+            'r' => {
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
+                if let Ok(hint) = RouteHint::from_bytes(&tag_data) {
+                    addr.route_hints.push(hint);
+                }
+            }
+            'f' => {
+                if let Ok(fallback) = Fallback::from_5bit(tag_data_5bit) {
+                    addr.fallbacks.push(fallback);
+                }
+            }
+            's' => {
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
+                if tag_data.len() == 32 {
+                    let mut secret = [0u8; 32];
+                    secret.copy_from_slice(&tag_data);
+                    addr.payment_secret = Some(secret);
+                }
+            }
+            'n' => {
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
+                if let Ok(pubkey) = PublicKey::from_slice(&tag_data) {
+                    addr.payee_pubkey = Some(pubkey);
+                }
+            }
+            'c' => {
+                let mut cltv = 0u64;
+                for &group in tag_data_5bit {
+                    cltv = (cltv << 5) | (group as u64);
+                }
+                addr.min_final_cltv_expiry = Some(cltv);
+            }
+            '9' => {
+                let tag_data =
+                    convert_bits(tag_data_5bit, 5, 8, false).map_err(ParseError::Bech32)?;
+                addr.features = Features::from_bytes(&tag_data);
+            }
             _ => {
                 // Unknown tag, skip
             }
         }
     }
 
-    let sig_bytes = convert_bits(sig_part, 5, 8, false)?;
+    let sig_bytes = convert_bits(sig_part, 5, 8, false).map_err(ParseError::Bech32)?;
     if sig_bytes.len() != 65 {
-        return Err("Invalid signature length".to_string());
+        return Err(ParseError::InvalidLength("Invalid signature length".to_string()).into());
     }
 
     let secp = Secp256k1::new();
     let mut msg_preimage = hrp.as_bytes().to_vec();
-    let data_bytes = convert_bits(data_part, 5, 8, false)?;
+    let data_bytes = convert_bits(data_part, 5, 8, true).map_err(ParseError::Bech32)?;
     msg_preimage.extend_from_slice(&data_bytes);
 
     let msg_hash = sha256::Hash::hash(&msg_preimage);
@@ -250,56 +735,281 @@ pub fn invoice_decode(invoice: &str) -> Result<InvoiceBolt11, String> {
 
     let recovery_id = secp256k1::ecdsa::RecoveryId::from_u8_masked(sig_bytes[64]);
     let sig = secp256k1::ecdsa::RecoverableSignature::from_compact(&sig_bytes[..64], recovery_id)
-        .unwrap();
+        .map_err(|_| SemanticError::InvalidSignature)?;
+
+    let recovered_pubkey = secp
+        .recover_ecdsa(msg, &sig)
+        .map_err(|_| SemanticError::InvalidSignature)?;
 
-    addr.pubkey = Some(secp.recover_ecdsa(msg, &sig).unwrap());
+    if let Some(expected) = addr.payee_pubkey {
+        if expected != recovered_pubkey {
+            return Err(SemanticError::PayeeKeyMismatch.into());
+        }
+    }
+
+    addr.pubkey = Some(recovered_pubkey);
     addr.signature = Some(sig_bytes[..64].to_vec());
 
+    addr.validate()?;
+
     Ok(addr)
 }
 
 fn tagged_field(tag: char, data: &[u8]) -> Result<Vec<u8>, String> {
-    let tag_val = CHARSET.find(tag).ok_or("Invalid tag character")? as u8;
     let data_5bit = convert_bits(data, 8, 5, true)?;
+    tagged_field_from_5bit(tag, &data_5bit)
+}
+
+fn tagged_field_from_5bit(tag: char, data_5bit: &[u8]) -> Result<Vec<u8>, String> {
+    let tag_val = CHARSET.find(tag).ok_or("Invalid tag character")? as u8;
     let length = data_5bit.len();
 
     let mut result = vec![tag_val, (length / 32) as u8, (length % 32) as u8];
-    result.extend_from_slice(&data_5bit);
+    result.extend_from_slice(data_5bit);
     Ok(result)
 }
 
-pub fn shorten_amount(amount: f64) -> String {
-    let mut amount = (amount * 1e12) as u64;
-    let units = ['p', 'n', 'u', 'm', ' '];
+/// States of the HRP state machine, in the order fields are expected to appear:
+/// `ln<currency><amount><si-prefix>`.
+enum HrpParseState {
+    Start,
+    ParseL,
+    ParseN,
+    ParseCurrencyPrefix,
+    ParseAmountNumber,
+    ParseAmountSiPrefix,
+}
 
-    for unit in units {
-        if amount % 1000 == 0 && unit != ' ' {
-            amount /= 1000;
-        } else {
-            break;
+/// Parses a bech32 human-readable part into its currency prefix and, if present,
+/// an exact millisatoshi amount. Replaces the old regex-based parser so large
+/// amounts round-trip through integers instead of `f64`.
+fn parse_hrp(hrp: &str) -> Result<(String, Option<u64>), String> {
+    let mut state = HrpParseState::Start;
+    let mut currency = String::new();
+    let mut amount_digits = String::new();
+    let mut si_prefix: Option<char> = None;
+
+    for c in hrp.chars() {
+        state = match (state, c) {
+            (HrpParseState::Start, 'l') => HrpParseState::ParseL,
+            (HrpParseState::ParseL, 'n') => HrpParseState::ParseN,
+            (HrpParseState::ParseN, c) if c.is_ascii_lowercase() => {
+                currency.push(c);
+                HrpParseState::ParseCurrencyPrefix
+            }
+            (HrpParseState::ParseCurrencyPrefix, c) if c.is_ascii_lowercase() => {
+                currency.push(c);
+                HrpParseState::ParseCurrencyPrefix
+            }
+            (HrpParseState::ParseCurrencyPrefix, c) if c.is_ascii_digit() => {
+                amount_digits.push(c);
+                HrpParseState::ParseAmountNumber
+            }
+            (HrpParseState::ParseAmountNumber, c) if c.is_ascii_digit() => {
+                amount_digits.push(c);
+                HrpParseState::ParseAmountNumber
+            }
+            (HrpParseState::ParseAmountNumber, c) if matches!(c, 'm' | 'u' | 'n' | 'p') => {
+                si_prefix = Some(c);
+                HrpParseState::ParseAmountSiPrefix
+            }
+            (state, c) => {
+                return Err(format!(
+                    "Unexpected character '{}' in HRP (state: {})",
+                    c,
+                    state.name()
+                ))
+            }
+        };
+    }
+
+    if !matches!(
+        state,
+        HrpParseState::ParseCurrencyPrefix
+            | HrpParseState::ParseAmountNumber
+            | HrpParseState::ParseAmountSiPrefix
+    ) {
+        return Err("Incomplete HRP".to_string());
+    }
+
+    if currency.is_empty() {
+        return Err("Missing currency prefix".to_string());
+    }
+
+    let amount_msat = if amount_digits.is_empty() {
+        None
+    } else {
+        let amount: u64 = amount_digits.parse().map_err(|_| "Invalid amount number")?;
+        Some(amount_to_msat(amount, si_prefix)?)
+    };
+
+    Ok((currency, amount_msat))
+}
+
+impl HrpParseState {
+    fn name(&self) -> &'static str {
+        match self {
+            HrpParseState::Start => "Start",
+            HrpParseState::ParseL => "ParseL",
+            HrpParseState::ParseN => "ParseN",
+            HrpParseState::ParseCurrencyPrefix => "ParseCurrencyPrefix",
+            HrpParseState::ParseAmountNumber => "ParseAmountNumber",
+            HrpParseState::ParseAmountSiPrefix => "ParseAmountSiPrefix",
+        }
+    }
+}
+
+fn amount_to_msat(amount: u64, si_prefix: Option<char>) -> Result<u64, String> {
+    match si_prefix {
+        None => amount
+            .checked_mul(100_000_000_000)
+            .ok_or_else(|| "Amount overflow".to_string()),
+        Some('m') => amount
+            .checked_mul(100_000_000)
+            .ok_or_else(|| "Amount overflow".to_string()),
+        Some('u') => amount
+            .checked_mul(100_000)
+            .ok_or_else(|| "Amount overflow".to_string()),
+        Some('n') => amount
+            .checked_mul(100)
+            .ok_or_else(|| "Amount overflow".to_string()),
+        Some('p') => {
+            if amount % 10 != 0 {
+                return Err("Amount in picobitcoin must be a multiple of 10".to_string());
+            }
+            Ok(amount / 10)
         }
+        Some(c) => Err(format!("Invalid amount SI prefix: {}", c)),
     }
+}
+
+pub fn shorten_amount(amount_msat: u64) -> Result<String, String> {
+    let mut amount = amount_msat
+        .checked_mul(10)
+        .ok_or_else(|| "Amount overflow".to_string())?;
+    let units = ['p', 'n', 'u', 'm'];
+    let mut unit_idx = 0;
 
-    if units[units.len() - 1] == ' ' {
-        amount.to_string()
+    while unit_idx < units.len() && amount % 1000 == 0 {
+        amount /= 1000;
+        unit_idx += 1;
+    }
+
+    if unit_idx == units.len() {
+        Ok(amount.to_string())
     } else {
-        format!("{}{}", amount, units[units.len() - 1])
+        Ok(format!("{}{}", amount, units[unit_idx]))
     }
 }
 
-pub fn unshorten_amount(amount: &str) -> Result<f64, String> {
-    let re = Regex::new(r"^(\d+)([pnum]?)$").unwrap();
-    let caps = re.captures(amount).ok_or("Invalid amount format")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PRIVKEY: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+    #[test]
+    fn route_hint_round_trips() {
+        let hop = RouteHintHop {
+            pubkey: [2u8; 33],
+            short_channel_id: 0x0102030405060708,
+            fee_base_msat: 1000,
+            fee_proportional_millionths: 10,
+            cltv_expiry_delta: 40,
+        };
+
+        let addr = InvoiceBolt11::new()
+            .with_paymenthash(vec![7u8; 32])
+            .add_description("test")
+            .add_route_hint(RouteHint(vec![hop.clone()]));
+
+        let invoice = invoice_encode(&addr, TEST_PRIVKEY).unwrap();
+        let decoded = invoice_decode(&invoice).unwrap();
+
+        assert_eq!(decoded.route_hints.len(), 1);
+        let decoded_hop = &decoded.route_hints[0].0[0];
+        assert_eq!(decoded_hop.pubkey, hop.pubkey);
+        assert_eq!(decoded_hop.short_channel_id, hop.short_channel_id);
+        assert_eq!(decoded_hop.fee_base_msat, hop.fee_base_msat);
+        assert_eq!(
+            decoded_hop.fee_proportional_millionths,
+            hop.fee_proportional_millionths
+        );
+        assert_eq!(decoded_hop.cltv_expiry_delta, hop.cltv_expiry_delta);
+    }
+
+    #[test]
+    fn fallback_round_trips() {
+        let addr = InvoiceBolt11::new()
+            .with_paymenthash(vec![7u8; 32])
+            .add_description("test")
+            .add_fallback(Fallback::PubKeyHash([9u8; 20]))
+            .add_fallback(Fallback::SegWitProgram {
+                version: 0,
+                program: vec![1u8; 20],
+            });
+
+        let invoice = invoice_encode(&addr, TEST_PRIVKEY).unwrap();
+        let decoded = invoice_decode(&invoice).unwrap();
+
+        assert_eq!(decoded.fallbacks.len(), 2);
+        assert!(matches!(decoded.fallbacks[0], Fallback::PubKeyHash(hash) if hash == [9u8; 20]));
+        assert!(
+            matches!(&decoded.fallbacks[1], Fallback::SegWitProgram { version: 0, program } if program == &vec![1u8; 20])
+        );
+    }
+
+    #[test]
+    fn segwit_fallback_program_length_is_bounded() {
+        let too_short = Fallback::SegWitProgram {
+            version: 0,
+            program: vec![1u8; 1],
+        };
+        assert!(too_short.to_5bit().is_err());
+
+        let too_long = Fallback::SegWitProgram {
+            version: 0,
+            program: vec![1u8; 41],
+        };
+        assert!(too_long.to_5bit().is_err());
+    }
+
+    #[test]
+    fn payment_secret_payee_pubkey_and_cltv_round_trip() {
+        let secp = Secp256k1::new();
+        let privkey_bytes: [u8; 32] = hex::decode(TEST_PRIVKEY).unwrap().try_into().unwrap();
+        let secret_key = SecretKey::from_byte_array(privkey_bytes).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &secret_key);
+
+        let addr = InvoiceBolt11::new()
+            .with_paymenthash(vec![7u8; 32])
+            .add_description("test")
+            .with_payment_secret([3u8; 32])
+            .with_feature_bit(Features::PAYMENT_SECRET)
+            .with_payee_pubkey(pubkey)
+            .with_min_final_cltv_expiry(144);
+
+        let invoice = invoice_encode(&addr, TEST_PRIVKEY).unwrap();
+        let decoded = invoice_decode(&invoice).unwrap();
+
+        assert_eq!(decoded.payment_secret, Some([3u8; 32]));
+        assert_eq!(decoded.payee_pubkey, Some(pubkey));
+        assert_eq!(decoded.min_final_cltv_expiry, Some(144));
+    }
+
+    #[test]
+    fn features_round_trip() {
+        let mut addr = InvoiceBolt11::new()
+            .with_paymenthash(vec![7u8; 32])
+            .add_description("test");
+        addr.features.set_var_onion_optin();
+        addr.features.set_basic_mpp();
 
-    let number: f64 = caps[1].parse().map_err(|_| "Invalid number")?;
-    let unit = caps.get(2).map(|m| m.as_str()).unwrap_or("");
+        let invoice = invoice_encode(&addr, TEST_PRIVKEY).unwrap();
+        let decoded = invoice_decode(&invoice).unwrap();
 
-    match unit {
-        "p" => Ok(number / 1e12),
-        "n" => Ok(number / 1e9),
-        "u" => Ok(number / 1e6),
-        "m" => Ok(number / 1e3),
-        "" => Ok(number),
-        _ => Err("Invalid unit".to_string()),
+        assert!(decoded.features.var_onion_optin());
+        assert!(decoded.features.basic_mpp());
+        assert!(!decoded.features.payment_secret());
     }
 }
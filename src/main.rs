@@ -3,7 +3,7 @@ pub mod bolt11;
 
 use clap::{Parser, Subcommand};
 
-use crate::bolt11::{InvoiceBolt11, invoice_decode, invoice_encode};
+use crate::bolt11::{invoice_decode, invoice_encode, Fallback, InvoiceBolt11};
 
 #[derive(Parser)]
 #[command(name = "bolt11-forge")]
@@ -72,8 +72,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
             println!("Currency: {}", addr.currency);
             println!("Payment hash: {}", hex::encode(&addr.paymenthash));
-            if let Some(amount) = addr.amount {
-                println!("Amount: {}", amount);
+            if let Some(amount_msat) = addr.amount_msat {
+                println!(
+                    "Amount: {} msat ({} BTC)",
+                    amount_msat,
+                    addr.amount_btc().unwrap()
+                );
             }
             println!(
                 "Timestamp: {} ({:?})",
@@ -105,6 +109,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
 
+            if !addr.features.is_empty() {
+                println!("Features: {:?}", addr.features.bits());
+            }
+
+            for fallback in &addr.fallbacks {
+                match fallback {
+                    Fallback::SegWitProgram { version, program } => {
+                        println!("Fallback: segwit v{} {}", version, hex::encode(program));
+                    }
+                    Fallback::PubKeyHash(hash) => {
+                        println!("Fallback: P2PKH {}", hex::encode(hash));
+                    }
+                    Fallback::ScriptHash(hash) => {
+                        println!("Fallback: P2SH {}", hex::encode(hash));
+                    }
+                }
+            }
+
             if verbose {
                 if let Some(sig) = &addr.signature {
                     println!("Signature: {}", hex::encode(sig));